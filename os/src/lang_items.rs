@@ -1,9 +1,15 @@
 //! The panic handler
+use crate::config::KERNEL_STACK_SIZE;
 use crate::sbi::shutdown;
 use crate::task::current_kstack_top;
 use core::arch::asm;
 use core::panic::PanicInfo;
 
+extern "C" {
+    fn stext();
+    fn etext();
+}
+
 /// Prints to the standard output, with a newline
 /// and shutdown.
 ///
@@ -31,17 +37,71 @@ fn panic(info: &PanicInfo) -> ! {
     shutdown()
 }
 
+/// Walks validated stack frames starting from the current `s0`/frame-
+/// pointer register, yielding `(frame_index, ra)` pairs.
+///
+/// Before each dereference it checks that the frame pointer lies within
+/// the current kernel stack, is 16-byte aligned, and that the recovered
+/// return address falls inside the kernel `.text` section
+/// (`stext`/`etext`); the walk stops on the first failed check instead of
+/// faulting, so a backtrace over a smashed stack degrades to "too short"
+/// rather than panicking inside the panic handler itself.
+struct FrameWalker {
+    fp: usize,
+    kstack_bottom: usize,
+    kstack_top: usize,
+    index: usize,
+}
+
+impl FrameWalker {
+    /// Start walking from the current frame, bounded to the current
+    /// kernel stack (`[kstack_top - KERNEL_STACK_SIZE, kstack_top)`).
+    unsafe fn new(kstack_top: usize) -> Self {
+        let mut fp: usize;
+        asm!("mv {}, s0", out(reg) fp);
+        Self {
+            fp,
+            kstack_bottom: kstack_top - KERNEL_STACK_SIZE,
+            kstack_top,
+            index: 0,
+        }
+    }
+
+    /// Whether `fp` lies within the current kernel stack and is 16-byte
+    /// aligned, i.e. it is safe to dereference `fp - 8` and `fp - 16`.
+    fn fp_is_valid(&self) -> bool {
+        self.fp % 16 == 0 && self.fp > self.kstack_bottom && self.fp <= self.kstack_top
+    }
+
+    /// Whether a recovered return address falls within the kernel's
+    /// `.text` section.
+    fn ra_is_valid(ra: usize) -> bool {
+        ra >= stext as usize && ra < etext as usize
+    }
+}
+
+impl Iterator for FrameWalker {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fp == self.kstack_top || !self.fp_is_valid() {
+            return None;
+        }
+        let ra = unsafe { *((self.fp - 8) as *const usize) };
+        if !Self::ra_is_valid(ra) {
+            return None;
+        }
+        let frame = (self.index, ra);
+        self.index += 1;
+        self.fp = unsafe { *((self.fp - 16) as *const usize) };
+        Some(frame)
+    }
+}
+
 unsafe fn backtrace() {
-    let mut fp: usize;
-    let stop = current_kstack_top();
-    asm!("mv {}, s0", out(reg) fp);
     println!("---START BACKTRACE---");
-    for i in 0..10 {
-        if fp == stop {
-            break;
-        }
-        println!("#{}:ra={:#x}", i, *((fp - 8) as *const usize));
-        fp = *((fp - 16) as *const usize);
+    for (i, ra) in FrameWalker::new(current_kstack_top()).take(10) {
+        println!("#{}:ra={:#x}", i, ra);
     }
     println!("---END   BACKTRACE---");
 }
@@ -3,6 +3,8 @@
 use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
 use core::fmt::{self, Debug, Formatter};
 
+use super::PageTableEntry;
+
 /// physical address
 ///
 /// SV39 supports a physical address bit width of 56 bits,
@@ -15,6 +17,12 @@ const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
 /// virtual address number width
 const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
 
+/// Base of the sign-extended "higher half" window the kernel's direct
+/// map lives in: physical page `ppn` is always reachable at
+/// `KERNEL_DIRECT_MAP_BASE + (ppn << PAGE_SIZE_BITS)`, giving the kernel
+/// a stable way to reach all of physical memory without an identity map.
+pub const KERNEL_DIRECT_MAP_BASE: usize = 0xFFFF_FFC0_0000_0000;
+
 // Definitions
 
 /// # physical address(SV39: 56bit)
@@ -83,6 +91,22 @@ pub struct PhysPageNum(pub usize);
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct VirtPageNum(pub usize);
 
+/// # Page table index
+///
+/// A single SV39 page table level index (VPN2/VPN1/VPN0), bounded to the
+/// 9 bits a table's 512 entries can be addressed with. The constructor
+/// masks any wider value down, so an out-of-range index can't be
+/// expressed once one of these has been built.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct PageTableIndex(usize);
+
+/// # Page offset
+///
+/// The offset within a 4KiB page (the low 12 bits of a `VirtAddr`/
+/// `PhysAddr`), bounded to `0..4096` by construction.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct PageOffset(usize);
+
 /// Debugging
 
 impl Debug for VirtAddr {
@@ -109,6 +133,18 @@ impl Debug for PhysPageNum {
     }
 }
 
+impl Debug for PageTableIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("PTI:{:#x}", self.0))
+    }
+}
+
+impl Debug for PageOffset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("OFF:{:#x}", self.0))
+    }
+}
+
 /// T: {PhysAddr, VirtAddr, PhysPageNum, VirtPageNum}
 /// T -> usize: T.0
 /// usize -> T: usize.into()
@@ -145,6 +181,20 @@ impl From<usize> for VirtPageNum {
     }
 }
 
+impl From<usize> for PageTableIndex {
+    /// Create a PageTableIndex storing only the low 9 bits (max 511).
+    fn from(v: usize) -> Self {
+        Self(v & ((1 << 9) - 1))
+    }
+}
+
+impl From<usize> for PageOffset {
+    /// Create a PageOffset storing only the low 12 bits (max 4095).
+    fn from(v: usize) -> Self {
+        Self(v & (PAGE_SIZE - 1))
+    }
+}
+
 impl From<PhysAddr> for usize {
     fn from(v: PhysAddr) -> Self {
         v.0
@@ -178,6 +228,18 @@ impl From<VirtPageNum> for usize {
     }
 }
 
+impl From<PageTableIndex> for usize {
+    fn from(v: PageTableIndex) -> Self {
+        v.0
+    }
+}
+
+impl From<PageOffset> for usize {
+    fn from(v: PageOffset) -> Self {
+        v.0
+    }
+}
+
 impl VirtAddr {
     pub fn floor(&self) -> VirtPageNum {
         VirtPageNum(self.0 / PAGE_SIZE)
@@ -185,17 +247,25 @@ impl VirtAddr {
     pub fn ceil(&self) -> VirtPageNum {
         VirtPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
     }
-    pub fn page_offset(&self) -> usize {
-        self.0 & (PAGE_SIZE - 1)
+    pub fn page_offset(&self) -> PageOffset {
+        PageOffset::from(self.0)
     }
     pub fn aligned(&self) -> bool {
-        self.page_offset() == 0
+        usize::from(self.page_offset()) == 0
+    }
+
+    /// Whether this address lies in the sign-extended "higher half" of
+    /// the SV39 address space, i.e. bit 38 is set (and therefore every
+    /// bit above it is set too once [`From<VirtAddr> for usize`] sign-
+    /// extends it back into a full 64-bit address).
+    pub fn is_higher_half(&self) -> bool {
+        self.0 & (1 << (VA_WIDTH_SV39 - 1)) != 0
     }
 }
 
 impl From<VirtAddr> for VirtPageNum {
     fn from(v: VirtAddr) -> Self {
-        assert_eq!(v.page_offset(), 0);
+        assert!(v.aligned());
         v.floor()
     }
 }
@@ -234,19 +304,19 @@ impl PhysAddr {
         PhysPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
     }
 
-    pub fn page_offset(&self) -> usize {
-        self.0 & (PAGE_SIZE - 1)
+    pub fn page_offset(&self) -> PageOffset {
+        PageOffset::from(self.0)
     }
 
     /// Is the Physical Address aligned to a multiple of PAGE_SIZE (default: 4096)?
     pub fn aligned(&self) -> bool {
-        self.page_offset() == 0
+        usize::from(self.page_offset()) == 0
     }
 }
 
 impl From<PhysAddr> for PhysPageNum {
     fn from(v: PhysAddr) -> Self {
-        assert_eq!(v.page_offset(), 0);
+        assert!(v.aligned());
         v.floor()
     }
 }
@@ -256,3 +326,42 @@ impl From<PhysPageNum> for PhysAddr {
         Self(v.0 << PAGE_SIZE_BITS)
     }
 }
+
+impl VirtPageNum {
+    /// Split the VPN into its three 9-bit SV39 level indexes,
+    /// `[VPN2, VPN1, VPN0]`, most significant first.
+    pub fn indexes(&self) -> [PageTableIndex; 3] {
+        let mut vpn = self.0;
+        let mut idx = [PageTableIndex::from(0); 3];
+        for i in (0..3).rev() {
+            idx[i] = PageTableIndex::from(vpn);
+            vpn >>= 9;
+        }
+        idx
+    }
+}
+
+impl PhysPageNum {
+    /// Interpret the physical page frame as an array of 512 page table
+    /// entries, as if it were a node of a multi-level page table.
+    pub fn get_pte_array(&self) -> &'static mut [PageTableEntry] {
+        let pa: PhysAddr = (*self).into();
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut PageTableEntry, 512) }
+    }
+
+    /// Interpret the physical page frame as a raw `PAGE_SIZE` byte
+    /// buffer, e.g. to copy a page's contents out to (or back in from) a
+    /// swap backing store.
+    pub fn get_bytes_array(&self) -> &'static mut [u8] {
+        let pa: PhysAddr = (*self).into();
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) }
+    }
+
+    /// Where this physical page frame is reachable through the kernel's
+    /// direct map: a fixed high virtual window covering all of physical
+    /// RAM, so callers don't have to rely on an identity map to touch it.
+    pub fn to_direct_map_va(&self) -> VirtAddr {
+        let pa: PhysAddr = (*self).into();
+        VirtAddr::from(KERNEL_DIRECT_MAP_BASE.wrapping_add(pa.0))
+    }
+}
@@ -4,7 +4,9 @@ use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
 
-use super::{frame_alloc, FrameTracker, PhysPageNum, VirtPageNum};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+
+use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -107,6 +109,171 @@ impl PageTableEntry {
     pub fn is_valid(&self) -> bool {
         (self.flags() & PTEFlags::V) != PTEFlags::empty()
     }
+
+    /// A PTE is a *leaf* (maps a page rather than pointing at the next
+    /// level table) precisely when any of R/W/X is set; a pointer PTE
+    /// has R=W=X=0.
+    pub fn is_leaf(&self) -> bool {
+        self.flags().intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+/// Page sizes supported by SV39 paging, i.e. how many levels of the
+/// multi-level page table a mapping walks before installing a leaf PTE.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    /// 4KiB page: walks all three levels, leaf installed at level 0 (VPN0).
+    Size4KiB,
+    /// 2MiB page: leaf installed at level 1 (VPN1); the level-0 table is
+    /// never allocated.
+    Size2MiB,
+    /// 1GiB page: leaf installed at level 2 (VPN2); the level-1 and
+    /// level-0 tables are never allocated.
+    Size1GiB,
+}
+
+impl PageSize {
+    /// The page table level a leaf PTE of this size is installed at
+    /// (2 = root/VPN2, 1 = VPN1, 0 = VPN0).
+    fn level(&self) -> usize {
+        match self {
+            PageSize::Size4KiB => 0,
+            PageSize::Size2MiB => 1,
+            PageSize::Size1GiB => 2,
+        }
+    }
+
+    /// Number of low-order VPN/PPN bits that must be zero for an address
+    /// to be aligned to this page size (9 bits per level below the leaf).
+    fn align_bits(&self) -> usize {
+        9 * self.level()
+    }
+}
+
+/// Bit offset of the RSW (Reserved for Supervisor SW) field within a PTE.
+const RSW_SHIFT: usize = 8;
+/// Mask for the 2-bit RSW field within a PTE.
+const RSW_MASK: usize = 0b11 << RSW_SHIFT;
+/// Sentinel swap slot meaning "no backing-store slot": the page was
+/// evicted without being written back (it was clean, `D` was 0) and
+/// should simply be demand-zeroed on the next fault.
+///
+/// Must fit in the 44-bit PPN field that [`PageTableEntry::new_swapped_out`]
+/// stashes it in, or it would get truncated on the way in and never
+/// round-trip back out of [`PageTableEntry::swap_slot`].
+const NO_SWAP_SLOT: usize = (1 << 44) - 1;
+
+/// The state a page's RSW bits encode, letting the swap subsystem tell a
+/// resident page from one that has been paged out without needing any
+/// storage beyond the PTE itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RswState {
+    /// The page is backed by a real physical frame; `V` is set normally.
+    Resident,
+    /// The page has been evicted: `V` is clear (so accessing it faults)
+    /// and the PPN field holds a swap-space slot index instead of a
+    /// physical frame.
+    SwappedOut,
+    /// Unused; reserved for future RSW-encoded states.
+    Reserved,
+}
+
+impl RswState {
+    fn from_bits(bits: usize) -> Self {
+        match (bits & RSW_MASK) >> RSW_SHIFT {
+            0 => RswState::Resident,
+            1 => RswState::SwappedOut,
+            _ => RswState::Reserved,
+        }
+    }
+
+    fn bits(&self) -> usize {
+        let state = match self {
+            RswState::Resident => 0,
+            RswState::SwappedOut => 1,
+            RswState::Reserved => 2,
+        };
+        state << RSW_SHIFT
+    }
+}
+
+impl PageTableEntry {
+    /// Build a PTE for a page that has just been swapped out: `V` stays
+    /// clear so the next access takes a page fault, the original R/W/X/U
+    /// flags are kept so they can be restored verbatim on fault-in, and
+    /// `slot` (a [`SwapSpace`] index, or [`NO_SWAP_SLOT`]) is stashed in
+    /// the PPN field since that space is otherwise unused while the page
+    /// is non-resident.
+    fn new_swapped_out(slot: usize, flags: PTEFlags) -> Self {
+        let mut pte = PageTableEntry {
+            bits: (slot << 10) | (flags - PTEFlags::V).bits() as usize,
+        };
+        pte.set_rsw(RswState::SwappedOut);
+        pte
+    }
+
+    /// The swap slot index stashed in the PPN field of a PTE marked
+    /// `SwappedOut` (meaningless for any other RSW state).
+    fn swap_slot(&self) -> usize {
+        self.bits >> 10
+    }
+
+    /// The per-page state encoded in this PTE's RSW bits.
+    pub fn rsw(&self) -> RswState {
+        RswState::from_bits(self.bits)
+    }
+
+    /// Overwrite this PTE's RSW bits, leaving everything else untouched.
+    pub fn set_rsw(&mut self, state: RswState) {
+        self.bits = (self.bits & !RSW_MASK) | state.bits();
+    }
+}
+
+/// A minimal stand-in for a swap backing store: each slot holds one
+/// page's worth of bytes, addressed by index. A real kernel would back
+/// this with a block device; this is enough to exercise the
+/// eviction/fault-in path without one.
+struct SwapSpace {
+    slots: Vec<[u8; PAGE_SIZE]>,
+    /// Indices of `slots` freed by [`SwapSpace::free_slot`] and available
+    /// for reuse, so a page that gets evicted and faulted back in
+    /// repeatedly doesn't grow `slots` without bound.
+    free: Vec<usize>,
+}
+
+impl SwapSpace {
+    fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Write a page out to a free slot (reusing one released by
+    /// `free_slot` if there is one) and return its index.
+    fn write_out(&mut self, data: &[u8]) -> usize {
+        let mut slot = [0u8; PAGE_SIZE];
+        slot.copy_from_slice(data);
+        if let Some(idx) = self.free.pop() {
+            self.slots[idx] = slot;
+            idx
+        } else {
+            self.slots.push(slot);
+            self.slots.len() - 1
+        }
+    }
+
+    /// Read a previously written slot back in.
+    fn read_in(&self, slot: usize) -> &[u8; PAGE_SIZE] {
+        &self.slots[slot]
+    }
+
+    /// Release a slot so a later `write_out` can reuse it. Must only be
+    /// called once a slot's page is no longer swapped out (faulted back
+    /// in, or unmapped).
+    fn free_slot(&mut self, slot: usize) {
+        self.free.push(slot);
+    }
 }
 
 /// # Page table
@@ -130,6 +297,16 @@ pub struct PageTable {
     /// When the lifecycle of the PageTable ends, those FrameTrackers in the vector frame are also recycled,
     /// which means that the physical page frame holding the multi-level PageTable node is recycled.
     frames: Vec<FrameTracker>,
+    /// Leaf data frames backing pages mapped through `map_anon`, owned
+    /// here (rather than by the caller, as `map`/`map_huge` assume) so
+    /// the swap subsystem can free and reallocate them as pages are
+    /// evicted and faulted back in. Scanned in order by the clock/
+    /// second-chance reclaim pass.
+    resident: Vec<(VirtPageNum, FrameTracker)>,
+    /// Position of the clock hand into `resident` for the next sweep.
+    clock_hand: usize,
+    /// Backing store for pages this table has swapped out.
+    swap: SwapSpace,
 }
 
 impl PageTable {
@@ -139,23 +316,54 @@ impl PageTable {
         PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
+            resident: Vec::new(),
+            clock_hand: 0,
+            swap: SwapSpace::new(),
         }
     }
 
     /// Get the next page table.
     /// If not found, create a new page table and return `None`.
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_pte_create_sized(vpn, PageSize::Size4KiB)
+    }
+
+    /// Walk down to the level matching `size`, allocating intermediate
+    /// (pointer) page tables as needed, and return the leaf slot for the
+    /// caller to fill in. For a 1GiB page this stops at VPN2 without ever
+    /// touching the VPN1/VPN0 tables.
+    fn find_pte_create_sized(
+        &mut self,
+        vpn: VirtPageNum,
+        size: PageSize,
+    ) -> Option<&mut PageTableEntry> {
+        // Re-mask to VPN_WIDTH_SV39 so a canonical higher-half VPN (top
+        // bits all 1 before sign extension) walks the same as its
+        // low-half counterpart.
+        let vpn = VirtPageNum::from(vpn.0);
         let idxs = vpn.indexes();
+        let stop = 2 - size.level();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             // Get page table and use 9 bits(Max:512) of virtual page number as index.
-            let pte = &mut ppn.get_pte_array()[*idx];
-            // is level 2 table?
-            if i == 2 {
+            let pte = &mut ppn.get_pte_array()[usize::from(*idx)];
+            if i == stop {
                 result = Some(pte);
                 break;
             }
+            // Checked unconditionally, before the is_valid()-gated
+            // allocation below: a swapped-out huge leaf has V clear but
+            // still has R/W/X set (and is_leaf() true), so gating this
+            // on is_valid() first would let the allocation branch
+            // silently clobber its swap-slot metadata instead of
+            // catching the overlap.
+            assert!(
+                !pte.is_leaf(),
+                "find_pte_create_sized: vpn {:#x} overlaps an existing huge page at level {}",
+                vpn.0,
+                2 - i
+            );
             if !pte.is_valid() {
                 let frame = frame_alloc().unwrap();
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
@@ -168,21 +376,38 @@ impl PageTable {
 
     /// Get the next page table.
     fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_pte_leveled(vpn).map(|(pte, _level)| pte)
+    }
+
+    /// Walk the page table for `vpn`, stopping at the first leaf PTE –
+    /// whatever level it was installed at (4KiB/2MiB/1GiB) – and
+    /// returning it together with that level.
+    ///
+    /// The returned PTE is handed back as-is, valid or not (mirroring the
+    /// historical `find_pte` contract that `map`/`unmap` build their own
+    /// asserts on top of): a leaf slot that was never mapped, or one that
+    /// has been swapped out, is still the leaf slot the walk was looking
+    /// for. Only an invalid *pointer* (non-leaf) PTE aborts the walk with
+    /// `None`, since there is no lower-level table to descend into.
+    fn find_pte_leveled(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
+        // Re-mask to VPN_WIDTH_SV39 so a canonical higher-half VPN (top
+        // bits all 1 before sign extension) walks the same as its
+        // low-half counterpart.
+        let vpn = VirtPageNum::from(vpn.0);
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
-            let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            let level = 2 - i;
+            let pte = &mut ppn.get_pte_array()[usize::from(*idx)];
+            if i == 2 || pte.is_leaf() {
+                return Some((pte, level));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        unreachable!()
     }
 
     #[allow(unused)]
@@ -209,12 +434,173 @@ impl PageTable {
     }
 
     #[allow(unused)]
+    /// Map a huge page (2MiB or 1GiB): the leaf PTE is installed at the
+    /// page-table level matching `size`, and the levels below it are left
+    /// unallocated, saving both page-table memory and TLB entries for
+    /// large contiguous regions (the kernel direct map, framebuffers).
+    ///
+    /// Both `vpn` and `ppn` must already be aligned to `size`.
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, size: PageSize) {
+        let align_mask = (1usize << size.align_bits()) - 1;
+        assert_eq!(
+            vpn.0 & align_mask,
+            0,
+            "vpn {:?} is not aligned to {:?}",
+            vpn,
+            size
+        );
+        assert_eq!(
+            ppn.0 & align_mask,
+            0,
+            "ppn {:?} is not aligned to {:?}",
+            ppn,
+            size
+        );
+        let pte = self.find_pte_create_sized(vpn, size).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    #[allow(unused)]
+    /// Map all of physical RAM, `[PhysAddr(0), mem_end)`, into the
+    /// kernel's higher-half direct map window (see
+    /// [`PhysPageNum::to_direct_map_va`]). Each region is mapped with the
+    /// largest leaf size that fits, falling back from 1GiB to 2MiB to
+    /// 4KiB at the tail so a `mem_end` that isn't 1GiB-aligned (the
+    /// common case: this tutorial's QEMU targets run with well under a
+    /// gigabyte of RAM) never over-maps past `mem_end` into memory that
+    /// isn't actually backed by RAM.
+    pub fn map_direct_map(&mut self, mem_end: PhysAddr, flags: PTEFlags) {
+        const GIB: usize = 1 << 30;
+        const MIB2: usize = 1 << 21;
+        let mut pa = 0usize;
+        while pa < mem_end.0 {
+            let ppn = PhysAddr(pa).floor();
+            let vpn = ppn.to_direct_map_va().floor();
+            if pa % GIB == 0 && mem_end.0 - pa >= GIB {
+                self.map_huge(vpn, ppn, flags, PageSize::Size1GiB);
+                pa += GIB;
+            } else if pa % MIB2 == 0 && mem_end.0 - pa >= MIB2 {
+                self.map_huge(vpn, ppn, flags, PageSize::Size2MiB);
+                pa += MIB2;
+            } else {
+                self.map(vpn, ppn, flags);
+                pa += PAGE_SIZE;
+            }
+        }
+    }
+
+    #[allow(unused)]
+    /// Unmap `vpn`, clearing its leaf PTE. Handles both pages still
+    /// resident (mapped through `map_anon`: the backing frame is dropped
+    /// and its `resident` entry removed) and pages currently swapped out
+    /// (their backing slot, if any, is released back to `swap`), so
+    /// `unmap` stays consistent with the reclaim/fault-in bookkeeping
+    /// those two paths rely on.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find_pte(vpn).unwrap();
-        assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        assert!(
+            pte.is_valid() || pte.rsw() == RswState::SwappedOut,
+            "vpn {:?} is invalid before unmapping",
+            vpn
+        );
+        if pte.rsw() == RswState::SwappedOut {
+            let slot = pte.swap_slot();
+            if slot != NO_SWAP_SLOT {
+                self.swap.free_slot(slot);
+            }
+        } else if let Some(idx) = self.resident.iter().position(|(v, _)| *v == vpn) {
+            self.resident.remove(idx);
+            if self.clock_hand > idx {
+                self.clock_hand -= 1;
+            }
+        }
+        let pte = self.find_pte(vpn).unwrap();
         *pte = PageTableEntry::empty();
     }
 
+    #[allow(unused)]
+    /// Map a freshly allocated, swappable anonymous page at `vpn`.
+    /// Unlike `map`, the backing frame is owned by the page table itself
+    /// (in `resident`) so the swap subsystem – `reclaim_one` and
+    /// `handle_page_fault` – can evict it and restore it later.
+    pub fn map_anon(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let frame = frame_alloc().unwrap();
+        let ppn = frame.ppn;
+        self.map(vpn, ppn, flags);
+        self.resident.push((vpn, frame));
+    }
+
+    #[allow(unused)]
+    /// Handle a page fault at `va`. If the faulting leaf PTE is marked
+    /// `SwappedOut`, allocate a fresh frame, fill it in (read back from
+    /// the swap store, or zero-fill for a page that was dropped clean),
+    /// and rewrite the leaf with `V` set so the faulting access can be
+    /// retried. Returns `false` if `va` isn't a page this table knows how
+    /// to fault in (e.g. a genuinely invalid access).
+    pub fn handle_page_fault(&mut self, va: VirtAddr) -> bool {
+        let vpn = va.floor();
+        let (slot, flags) = match self.find_pte(vpn) {
+            Some(pte) if pte.rsw() == RswState::SwappedOut => (pte.swap_slot(), pte.flags()),
+            _ => return false,
+        };
+        let frame = frame_alloc().unwrap();
+        if slot == NO_SWAP_SLOT {
+            frame.ppn.get_bytes_array().fill(0);
+        } else {
+            frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(self.swap.read_in(slot));
+            self.swap.free_slot(slot);
+        }
+        let ppn = frame.ppn;
+        let pte = self.find_pte(vpn).unwrap();
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.resident.push((vpn, frame));
+        true
+    }
+
+    #[allow(unused)]
+    /// Run one step of a clock/second-chance sweep over the anonymous
+    /// pages this table has handed out through `map_anon`, looking for an
+    /// eviction candidate. A page whose `A` bit is set gets a second
+    /// chance (its `A` bit is cleared and the sweep moves on); a page
+    /// whose `A` bit is already clear is evicted: written back to the
+    /// swap store if `D` is set, dropped otherwise. Returns the evicted
+    /// VPN, or `None` if no page was evicted: either there are no
+    /// resident anonymous pages, or every page visited in this pass (one
+    /// visit per resident page) still had `A` set and only had it cleared.
+    pub fn reclaim_one(&mut self) -> Option<VirtPageNum> {
+        let n = self.resident.len();
+        for _ in 0..n {
+            let idx = self.clock_hand % n;
+            let vpn = self.resident[idx].0;
+            self.clock_hand = idx + 1;
+            let pte = self.find_pte(vpn).unwrap();
+            let flags = pte.flags();
+            if flags.contains(PTEFlags::A) {
+                let ppn = pte.ppn();
+                *pte = PageTableEntry::new(ppn, flags - PTEFlags::A);
+                continue;
+            }
+            let (_, frame) = self.resident.remove(idx);
+            let slot = if flags.contains(PTEFlags::D) {
+                self.swap.write_out(frame.ppn.get_bytes_array())
+            } else {
+                NO_SWAP_SLOT
+            };
+            drop(frame);
+            if self.clock_hand > idx {
+                self.clock_hand -= 1;
+            }
+            let pte = self.find_pte(vpn).unwrap();
+            *pte = PageTableEntry::new_swapped_out(slot, flags);
+            return Some(vpn);
+        }
+        None
+    }
+
     #[allow(unused)]
     /// Temporarily used to get arguments from user space.
     ///
@@ -227,6 +613,9 @@ impl PageTable {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
             frames: Vec::new(),
+            resident: Vec::new(),
+            clock_hand: 0,
+            swap: SwapSpace::new(),
         }
     }
 
@@ -235,4 +624,22 @@ impl PageTable {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte)
     }
+
+    #[allow(unused)]
+    /// Translate an arbitrary virtual address to its physical address.
+    ///
+    /// Walks the page table, stops at the first leaf PTE found – whatever
+    /// level it lives at (4KiB/2MiB/1GiB) – and composes the physical
+    /// address by taking the leaf's PPN and OR-ing in the low bits of `va`
+    /// below that level's boundary (12 bits for a 4KiB leaf, 21 for 2MiB,
+    /// 30 for 1GiB). Returns `None` if the walk hits an invalid PTE first.
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        let (pte, level) = self.find_pte_leveled(va.floor())?;
+        if !pte.is_valid() {
+            return None;
+        }
+        let leaf_pa: PhysAddr = pte.ppn().into();
+        let low_bits = va.0 & ((1 << (PAGE_SIZE_BITS + 9 * level)) - 1);
+        Some(PhysAddr(leaf_pa.0 + low_bits))
+    }
 }